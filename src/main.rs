@@ -2,24 +2,29 @@ use clap::Parser;
 use regex::Regex;
 use reqwest::Client;
 use serde_yaml::{self, Value};
+use serde_json::Value as JsonValue;
 use std::env;
 use std::io::{self, BufRead, Read, Write};
-use std::net::{IpAddr, Ipv4Addr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::path::{Path, PathBuf};
 use std::process::exit;
 use std::str::FromStr;
 use std::sync::Arc;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::{error::Error, fs::create_dir_all, fs::File};
-use tokio;
-use tokio::io::AsyncWriteExt;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::UnixListener;
 use tokio::sync::mpsc::channel;
+use tokio::sync::RwLock;
 use tokio::task::JoinSet;
+use sd_notify::NotifyState;
 use trust_dns_resolver::{
-    config::{ResolverConfig, ResolverOpts},
-    AsyncResolver,
+    config::{NameServerConfig, Protocol, ResolverConfig, ResolverOpts},
+    TokioAsyncResolver,
 };
 
+type Resolver = TokioAsyncResolver;
+
 // ANSI escape codes
 const BLUE: &str = "\x1b[34m";
 const RED: &str = "\x1b[31m";
@@ -27,6 +32,7 @@ const YELLOW: &str = "\x1b[33m";
 const RESET: &str = "\x1b[0m";
 
 const IPV4_CIDR_REGEX: &str = r#"(([0-9]|[1-9][0-9]|1[0-9]{2}|2[0-4][0-9]|25[0-5])\.){3}([0-9]|[1-9][0-9]|1[0-9]{2}|2[0-4][0-9]|25[0-5])(/(3[0-2]|[1-2][0-9]|[0-9]))"#;
+const IPV6_CIDR_REGEX: &str = r#"([0-9a-fA-F]{0,4}:){2,7}[0-9a-fA-F]{0,4}(/(12[0-8]|1[01][0-9]|[1-9][0-9]|[0-9]))"#;
 const CONTENT: &str = r#"Providers:
     - https://api.fastly.com/public-ip-list
     - https://www.cloudflare.com/ips-v4
@@ -35,14 +41,24 @@ const CONTENT: &str = r#"Providers:
     - https://cachefly.cachefly.net/ips/rproxy.txt
     - https://docs-be.imperva.com/api/bundle/z-kb-articles-km/page/c85245b7.html
     - http://edge.sotoon.ir/ip-list.json
-    - https://docs.oracle.com/en-us/iaas/tools/public_ip_ranges.json
+    - url: https://docs.oracle.com/en-us/iaas/tools/public_ip_ranges.json
+      format: json
+      path: regions[].cidrs[].cidr
     - https://raw.githubusercontent.com/m333rl1n/cdnx/main/static-CIDRs.txt
     - https://my.incapsula.com/api/integration/v1/ips
 
 # default interval is 2 day
 Interval: 172800
 
-# TODO:  use custom DNS server
+# Custom DNS servers (optional, overrides the system resolver); protocol is
+# one of udp | tcp | tls | https. TlsName is the hostname to verify the
+# server's certificate against and is required for tls/https.
+# DNS:
+#   Protocol: tls
+#   TlsName: cloudflare-dns.com
+#   Servers:
+#     - 1.1.1.1
+#     - 1.0.0.1
 "#;
 
 #[derive(Parser, Debug)]
@@ -62,10 +78,43 @@ struct Args {
     /// Verbose mode
     #[arg(short, default_value_t = false)]
     verbose: bool,
+
+    /// Run as a resident daemon, serving classification queries over a Unix socket
+    #[arg(long, default_value_t = false)]
+    daemon: bool,
+
+    /// Unix socket path to listen on in daemon mode (default: ~/.config/cdnx/cdnx.sock)
+    #[arg(long)]
+    socket: Option<PathBuf>,
+
+    /// Custom DNS servers to use instead of the system resolver (comma-separated IPs)
+    #[arg(long)]
+    dns: Option<String>,
+
+    /// Protocol for the custom DNS servers: udp, tcp, tls, or https
+    #[arg(long, default_value = "udp")]
+    dns_protocol: String,
+
+    /// TLS server name to verify the custom DNS servers' certificate against
+    /// (required for tls/https; DoT/DoH validate the cert by hostname, not by IP)
+    #[arg(long)]
+    dns_tls_name: Option<String>,
+
+    /// DNS lookup timeout in seconds
+    #[arg(long)]
+    dns_timeout: Option<u64>,
+
+    /// DNS lookup attempts before giving up
+    #[arg(long)]
+    dns_attempts: Option<usize>,
+
+    /// Force an immediate CIDR refresh, bypassing the configured Interval
+    #[arg(long, alias = "update", default_value_t = false)]
+    force: bool,
 }
 
 fn logger(color: &str, sign: &str, msg: &str) {
-    writeln!(io::stderr(), "[{}{}{RESET}] {}", color, sign, msg).unwrap();
+    eprintln!("[{}{}{RESET}] {}", color, sign, msg);
 }
 
 macro_rules! error {
@@ -84,9 +133,132 @@ macro_rules! warn {
     };
 }
 
+/// A single `Providers:` entry, either a bare URL (plain-text/regex scraping,
+/// the historical behavior) or a map naming a structured `format` to parse.
+struct Provider {
+    url: String,
+    format: String,
+    json_path: Option<String>,
+}
+
+fn parse_provider(value: &Value) -> Option<Provider> {
+    match value {
+        Value::String(url) => Some(Provider {
+            url: url.clone(),
+            format: "text".to_string(),
+            json_path: None,
+        }),
+        Value::Mapping(_) => {
+            let url = value.get("url")?.as_str()?.to_string();
+            let format = value
+                .get("format")
+                .and_then(|f| f.as_str())
+                .unwrap_or("text")
+                .to_string();
+            let json_path = value
+                .get("path")
+                .and_then(|p| p.as_str())
+                .map(|s| s.to_string());
+            Some(Provider {
+                url,
+                format,
+                json_path,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Walk a dotted JSON path where a `[]` suffix on a segment means "descend
+/// into every element of this array", collecting every string found at the
+/// final segment. e.g. `regions[].cidrs[].cidr` over Oracle's
+/// `public_ip_ranges.json` yields every `cidr` field nested under it.
+fn walk_json_path(value: &JsonValue, segments: &[&str], out: &mut Vec<String>) {
+    match segments {
+        [] => {
+            if let Some(s) = value.as_str() {
+                out.push(s.to_string());
+            }
+        }
+        [segment, rest @ ..] => {
+            let is_array = segment.ends_with("[]");
+            let key = segment.trim_end_matches("[]");
+            let Some(next) = value.get(key) else {
+                return;
+            };
+            if is_array {
+                if let Some(items) = next.as_array() {
+                    for item in items {
+                        walk_json_path(item, rest, out);
+                    }
+                }
+            } else {
+                walk_json_path(next, rest, out);
+            }
+        }
+    }
+}
+
+/// Extract CIDR strings from a provider's response body, either by walking
+/// its configured JSON path or, for plain text, by regex scraping.
+fn extract_cidrs(provider: &Provider, body: &str, reg_v4: &Regex, reg_v6: &Regex) -> Vec<String> {
+    if provider.format == "json" {
+        let mut out = vec![];
+        if let Ok(json) = serde_json::from_str::<JsonValue>(body) {
+            if let Some(path) = &provider.json_path {
+                let segments: Vec<&str> = path.split('.').collect();
+                walk_json_path(&json, &segments, &mut out);
+            } else if let Some(items) = json.as_array() {
+                for item in items {
+                    if let Some(s) = item.as_str() {
+                        out.push(s.to_string());
+                    }
+                }
+            }
+        }
+        out
+    } else {
+        reg_v4
+            .captures_iter(body)
+            .chain(reg_v6.captures_iter(body))
+            .map(|c| c.get(0).unwrap().as_str().to_string())
+            .collect()
+    }
+}
+
+/// Path to the small sibling state file that tracks the last successful
+/// fetch, kept separate from `config.yaml` so rewriting it never clobbers
+/// the user's comments (e.g. the `DNS:` example `CONTENT` ships commented out).
+fn last_updated_file_path() -> PathBuf {
+    PathBuf::from(env::var("HOME").unwrap() + "/.config/cdnx").join("last_updated")
+}
+
+/// Stamp the state file with the current epoch time, so staleness can be
+/// judged without relying on filesystem mtime support.
+fn write_last_updated() -> Result<(), Box<dyn Error + Send + Sync>> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    File::create(last_updated_file_path())?.write_all(now.to_string().as_bytes())?;
+    Ok(())
+}
+
+/// Read the epoch timestamp of the last successful fetch, if any.
+fn read_last_updated() -> Option<u64> {
+    let mut buffer = String::new();
+    File::open(last_updated_file_path())
+        .ok()?
+        .read_to_string(&mut buffer)
+        .ok()?;
+    buffer.trim().parse().ok()
+}
+
 /// Fetch new CIDRs from providers
-async fn fetch_new_data(providers: &Value, path: &Path, verbose: bool) -> Result<(), Box<dyn Error>> {
-    let reg = Regex::new(IPV4_CIDR_REGEX).unwrap();
+async fn fetch_new_data(
+    providers: &Value,
+    cidr_file_path: &Path,
+    verbose: bool,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let reg_v4 = Regex::new(IPV4_CIDR_REGEX).unwrap();
+    let reg_v6 = Regex::new(IPV6_CIDR_REGEX).unwrap();
     if verbose {
         info!("Updating ...");
     }
@@ -97,23 +269,25 @@ async fn fetch_new_data(providers: &Value, path: &Path, verbose: bool) -> Result
         .build()
         .unwrap();
 
-    for url_value in providers.as_sequence().unwrap().iter() {
-        let url = url_value.as_str().unwrap().to_string();
-        let r = reg.clone();
+    for provider_value in providers.as_sequence().unwrap().iter() {
+        let Some(provider) = parse_provider(provider_value) else {
+            continue;
+        };
+        let r4 = reg_v4.clone();
+        let r6 = reg_v6.clone();
         let cx_clone = cx.clone();
         let client_clone = client.clone();
 
         let handle = tokio::spawn(async move {
+            let url = provider.url.clone();
             match client_clone.get(url.clone()).send().await {
                 Ok(response) => {
                     if response.status().is_success() {
                         // Read the response body
                         let body = response.text().await.unwrap();
-                        // Find CIDRs with regex in response body
-                        let cidrs = r.captures_iter(&body);
-                        for cidr in cidrs.into_iter() {
-                            let c = cidr.get(0).unwrap().as_str().to_string();
-                            cx_clone.send(c).await.unwrap();
+                        // Find CIDRs per the provider's configured format
+                        for cidr in extract_cidrs(&provider, &body, &r4, &r6) {
+                            cx_clone.send(cidr).await.unwrap();
                         }
 
                         if verbose {
@@ -141,7 +315,10 @@ async fn fetch_new_data(providers: &Value, path: &Path, verbose: bool) -> Result
         handles.push(handle);
     }
 
-    let mut file: tokio::fs::File = tokio::fs::File::create(path).await.unwrap();
+    // write to a temp file first so a partial/failed fetch can't corrupt the
+    // cache `read_cidrs` later depends on; only rename over it on success
+    let tmp_path = PathBuf::from(format!("{}.tmp", cidr_file_path.display()));
+    let mut file: tokio::fs::File = tokio::fs::File::create(&tmp_path).await.unwrap();
     let mut is_err = true;
     drop(cx);
     while let Some(i) = rx.recv().await {
@@ -150,111 +327,446 @@ async fn fetch_new_data(providers: &Value, path: &Path, verbose: bool) -> Result
     }
 
     if is_err {
-        error!("Could't fetch any CIDR :(");
-        exit(1);
-    } else if verbose {
+        let _ = tokio::fs::remove_file(&tmp_path).await;
+        return Err("Could't fetch any CIDR :(".into());
+    }
+
+    drop(file);
+    tokio::fs::rename(&tmp_path, cidr_file_path).await?;
+    write_last_updated()?;
+    if verbose {
         info!("Updated successfully")
     }
 
     Ok(())
 }
 
-async fn check_updates(verbose: bool) -> Result<(), Box<dyn Error>> {
+async fn check_updates(verbose: bool, force: bool) -> Result<(), Box<dyn Error + Send + Sync>> {
     let config_dir = PathBuf::from(env::var("HOME").unwrap() + "/.config/cdnx");
     let config_file_path = config_dir.join("config.yaml");
     let cidr_file_path = config_dir.join("cidr.txt");
 
-    let mut buffer = String::new();
-    let yaml_data: serde_yaml::Value;
-    let providers: Option<&Value>;
-    let mut _result: Vec<String> = vec![];
-
-    // if "~/.config/cdnx" and "~/.config/cdnx/config.yaml" exists
-    if config_dir.as_path().exists() && config_file_path.as_path().exists() {
-        // open "~/.config/cdnx/config.yaml" and read its data
-        let mut file = File::open(config_file_path.as_path()).unwrap();
-        let _ = file.read_to_string(&mut buffer);
-
-        // parse YAML data
-        yaml_data = serde_yaml::from_str(&buffer).unwrap();
-        providers = Some(yaml_data.get("Providers").unwrap());
-
-        // get Interval value and if not exists use default 172800s
-        let interval = match yaml_data.get("Interval") {
-            Some(value) => value.as_u64().unwrap_or(172800),
-            _ => 172800,
-        };
-
-        // if "~/.config/cdnx/cidr.txt" exists
-        if cidr_file_path.as_path().exists() {
-            let now = SystemTime::now();
-            // only works on linux ext4 file systems; TODO: write last update in "~/.config/cdnx/config.yaml"
-            let modified_time = cidr_file_path.metadata().unwrap().modified().unwrap();
-            // calculate time passed from last update
-            let gap = now.duration_since(modified_time).unwrap();
-
-            // if time passed from last update was lower than 2 days
-            if gap.as_secs() > interval {
-                // fetch new data from providers
-                fetch_new_data(&providers.unwrap(), &cidr_file_path, verbose).await?;
-            }
-        }
-    } else {
-        //create "~/.config/cdnx"
-        let _ = create_dir_all(config_dir);
-        // create "~/.config/cdnx/config.yaml" and write default value
+    // if "~/.config/cdnx" or "~/.config/cdnx/config.yaml" is missing, create
+    // them with the default config before reading it below
+    if !config_dir.as_path().exists() || !config_file_path.as_path().exists() {
+        let _ = create_dir_all(&config_dir);
         let mut file = File::create(config_file_path.as_path()).unwrap();
         let _ = file.write_all(CONTENT.as_bytes());
+    }
 
-        // parse default YAML data and get providers list
-        yaml_data = serde_yaml::from_str(&CONTENT).unwrap();
-        providers = Some(yaml_data.get("Providers").unwrap());
+    // open "~/.config/cdnx/config.yaml" and parse it
+    let mut buffer = String::new();
+    File::open(config_file_path.as_path())
+        .unwrap()
+        .read_to_string(&mut buffer)
+        .unwrap();
+    let yaml_data: serde_yaml::Value = serde_yaml::from_str(&buffer).unwrap();
+    let providers = yaml_data.get("Providers").unwrap();
 
-        // fetch new data from providers
-        fetch_new_data(&providers.unwrap(), &cidr_file_path, verbose).await?;
+    // get Interval value and if not exists use default 172800s
+    let interval = match yaml_data.get("Interval") {
+        Some(value) => value.as_u64().unwrap_or(172800),
+        _ => 172800,
+    };
+
+    // cross-platform staleness: compare against the epoch we persisted
+    // ourselves in the sibling state file, instead of the cidr.txt file's
+    // mtime (which only works on filesystems that actually track it)
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let gap = match read_last_updated() {
+        Some(last_updated) => now.saturating_sub(last_updated),
+        None => u64::MAX,
+    };
+
+    if force || gap > interval || !cidr_file_path.as_path().exists() {
+        fetch_new_data(providers, &cidr_file_path, verbose).await?;
     }
     Ok(())
 }
 
-fn is_cdn(cidrs: &Vec<String>, ip: &str) -> bool {
-    for cidr_str in cidrs {
-        if let Ok((network_ip, prefix_len)) = parse_cidr(&cidr_str) {
-            if let Ok(ip) = ip.parse::<Ipv4Addr>() {
-                let is_in_range = is_ip_in_cidr(ip, network_ip, prefix_len);
-                if is_in_range {
-                    return true;
+/// A parsed CIDR network, keeping the address family it was declared in.
+enum Cidr {
+    V4(Ipv4Addr, u8),
+    V6(Ipv6Addr, u8),
+}
+
+/// A node in a binary radix trie keyed on address bits, MSB first.
+/// A node marked `covered` means every address below it is inside some CIDR,
+/// so a lookup can stop as soon as it passes through one (longest-prefix
+/// semantics fall out of "first covered node wins").
+#[derive(Default)]
+struct TrieNode {
+    covered: bool,
+    children: [Option<Box<TrieNode>>; 2],
+}
+
+impl TrieNode {
+    fn insert(&mut self, bits: impl Iterator<Item = bool>) {
+        let mut node = self;
+        for bit in bits {
+            if node.covered {
+                return;
+            }
+            node = node.children[bit as usize].get_or_insert_with(Default::default);
+        }
+        node.covered = true;
+        node.children = [None, None];
+    }
+
+    fn contains(&self, bits: impl Iterator<Item = bool>) -> bool {
+        let mut node = self;
+        for bit in bits {
+            if node.covered {
+                return true;
+            }
+            match &node.children[bit as usize] {
+                Some(child) => node = child,
+                None => return false,
+            }
+        }
+        node.covered
+    }
+}
+
+/// Longest-prefix-match index over every CIDR, built once so lookups are
+/// O(address width) pointer hops instead of re-parsing and re-scanning the
+/// whole CIDR list for every IP.
+#[derive(Default)]
+struct CidrSet {
+    v4: TrieNode,
+    v6: TrieNode,
+}
+
+fn bits_msb(value: u128, width: u32, prefix_len: u8) -> impl Iterator<Item = bool> {
+    (0..prefix_len as u32).map(move |i| (value >> (width - 1 - i)) & 1 == 1)
+}
+
+impl CidrSet {
+    fn build(cidrs: &[String]) -> Self {
+        let mut set = CidrSet::default();
+        for cidr_str in cidrs {
+            match parse_cidr(cidr_str) {
+                Ok(Cidr::V4(ip, prefix_len)) => {
+                    set.v4.insert(bits_msb(u32::from(ip) as u128, 32, prefix_len));
                 }
+                Ok(Cidr::V6(ip, prefix_len)) => {
+                    set.v6.insert(bits_msb(u128::from(ip), 128, prefix_len));
+                }
+                Err(_) => {}
             }
         }
+        set
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        match ip {
+            IpAddr::V4(ip) => self.v4.contains(bits_msb(u32::from(ip) as u128, 32, 32)),
+            IpAddr::V6(ip) => self.v6.contains(bits_msb(u128::from(ip), 128, 128)),
+        }
+    }
+}
+
+fn is_cdn(cidr_set: &CidrSet, ip: &str) -> bool {
+    match ip.parse::<IpAddr>() {
+        Ok(ip) => cidr_set.contains(ip),
+        Err(_) => false,
     }
-    false
 }
 
-fn parse_cidr(cidr: &str) -> Result<(Ipv4Addr, u8), Box<dyn std::error::Error>> {
+fn parse_cidr(cidr: &str) -> Result<Cidr, Box<dyn std::error::Error + Send + Sync>> {
     let parts: Vec<&str> = cidr.split('/').collect();
     if parts.len() != 2 {
         return Err("Invalid CIDR format".into());
     }
 
-    let ip = Ipv4Addr::from_str(parts[0])?;
     let prefix_len: u8 = parts[1].parse()?;
 
-    if prefix_len > 32 {
-        return Err("Prefix length must be between 0 and 32".into());
+    if let Ok(ip) = Ipv4Addr::from_str(parts[0]) {
+        if prefix_len > 32 {
+            return Err("Prefix length must be between 0 and 32".into());
+        }
+        return Ok(Cidr::V4(ip, prefix_len));
+    }
+
+    let ip = Ipv6Addr::from_str(parts[0])?;
+    if prefix_len > 128 {
+        return Err("Prefix length must be between 0 and 128".into());
+    }
+    Ok(Cidr::V6(ip, prefix_len))
+}
+
+/// Read the optional `DNS:` section out of the config file: a list of
+/// nameserver IPs, an optional protocol, and an optional TLS server name.
+/// Absent/unreadable config means "use the system resolver", same as before
+/// this existed.
+fn load_dns_config() -> (Vec<String>, Option<String>, Option<String>) {
+    let config_file_path =
+        PathBuf::from(env::var("HOME").unwrap() + "/.config/cdnx").join("config.yaml");
+    let mut buffer = String::new();
+    let Ok(mut file) = File::open(&config_file_path) else {
+        return (vec![], None, None);
+    };
+    let _ = file.read_to_string(&mut buffer);
+    let Ok(yaml_data) = serde_yaml::from_str::<Value>(&buffer) else {
+        return (vec![], None, None);
+    };
+    let Some(dns) = yaml_data.get("DNS") else {
+        return (vec![], None, None);
+    };
+
+    let servers = dns
+        .get("Servers")
+        .and_then(|s| s.as_sequence())
+        .map(|seq| {
+            seq.iter()
+                .filter_map(|v| v.as_str().map(|s| s.to_string()))
+                .collect()
+        })
+        .unwrap_or_default();
+    let protocol = dns
+        .get("Protocol")
+        .and_then(|p| p.as_str())
+        .map(|s| s.to_string());
+    let tls_name = dns
+        .get("TlsName")
+        .and_then(|p| p.as_str())
+        .map(|s| s.to_string());
+    (servers, protocol, tls_name)
+}
+
+fn parse_dns_protocol(protocol: &str) -> Protocol {
+    match protocol.to_lowercase().as_str() {
+        "tcp" => Protocol::Tcp,
+        "tls" => Protocol::Tls,
+        "https" => Protocol::Https,
+        _ => Protocol::Udp,
+    }
+}
+
+/// Build the resolver config/opts. `--dns` (with `--dns-protocol`) replaces
+/// the config file's `DNS:` servers and protocol; without `--dns`,
+/// `--dns-protocol` is ignored and the section's `Protocol` (default udp) is
+/// used. `--dns-tls-name` overrides `TlsName` either way. Falls back to
+/// `ResolverConfig::default()` (the system resolver) when no servers are set.
+fn build_resolver_config(args: &Args) -> Result<(ResolverConfig, ResolverOpts), Box<dyn Error + Send + Sync>> {
+    let (cfg_servers, cfg_protocol, cfg_tls_name) = load_dns_config();
+    let (servers, protocol, tls_name) = match &args.dns {
+        Some(dns) => (
+            dns.split(',').map(|s| s.trim().to_string()).collect(),
+            args.dns_protocol.clone(),
+            args.dns_tls_name.clone(),
+        ),
+        None => (
+            cfg_servers,
+            cfg_protocol.unwrap_or_else(|| "udp".to_string()),
+            args.dns_tls_name.clone().or(cfg_tls_name),
+        ),
+    };
+
+    let mut resolver_opts = ResolverOpts::default();
+    if let Some(timeout) = args.dns_timeout {
+        resolver_opts.timeout = Duration::from_secs(timeout);
+    }
+    if let Some(attempts) = args.dns_attempts {
+        resolver_opts.attempts = attempts;
+    }
+
+    let servers: Vec<String> = servers;
+    if servers.is_empty() {
+        return Ok((ResolverConfig::default(), resolver_opts));
+    }
+
+    let protocol = parse_dns_protocol(&protocol);
+    if matches!(protocol, Protocol::Tls | Protocol::Https) && tls_name.is_none() {
+        return Err("DNS protocol tls/https requires a TLS server name \
+                     (set DNS.TlsName in config.yaml or pass --dns-tls-name)"
+            .into());
+    }
+    let port = match protocol {
+        Protocol::Tls => 853,
+        Protocol::Https => 443,
+        _ => 53,
+    };
+    let mut resolver_config = ResolverConfig::new();
+    for server in &servers {
+        let ip: IpAddr = server.parse()?;
+        resolver_config.add_name_server(NameServerConfig {
+            socket_addr: SocketAddr::new(ip, port),
+            protocol,
+            tls_dns_name: tls_name.clone(),
+            trust_negative_responses: false,
+            bind_addr: None,
+            tls_config: None,
+        });
+    }
+    Ok((resolver_config, resolver_opts))
+}
+
+/// Resolve a domain (or pass an already-literal IP straight through) to a
+/// single address, trying A records before falling back to AAAA.
+async fn resolve_ip(domain: &str, resolver: &Resolver) -> Option<String> {
+    if domain.parse::<IpAddr>().is_ok() {
+        return Some(domain.to_string());
+    }
+    let fqdn = domain.trim_end_matches('.').to_owned() + ".";
+    if let Ok(lookup_result) = resolver.ipv4_lookup(&fqdn).await {
+        if let Some(ip) = lookup_result.iter().next() {
+            return Some(ip.to_string());
+        }
+    }
+    if let Ok(lookup_result) = resolver.ipv6_lookup(&fqdn).await {
+        if let Some(ip) = lookup_result.iter().next() {
+            return Some(ip.to_string());
+        }
+    }
+    None
+}
+
+/// Read `Providers:`/`Interval:` out of the already-initialized config file.
+fn load_config() -> Result<(Value, u64), Box<dyn Error + Send + Sync>> {
+    let config_file_path =
+        PathBuf::from(env::var("HOME").unwrap() + "/.config/cdnx").join("config.yaml");
+    let mut buffer = String::new();
+    File::open(&config_file_path)?.read_to_string(&mut buffer)?;
+    let yaml_data: Value = serde_yaml::from_str(&buffer)?;
+    let providers = yaml_data.get("Providers").ok_or("missing Providers")?.clone();
+    let interval = yaml_data
+        .get("Interval")
+        .and_then(|v| v.as_u64())
+        .unwrap_or(172800);
+    Ok((providers, interval))
+}
+
+/// Force a refresh right now, bypassing the `Interval` freshness check.
+async fn refresh_now(verbose: bool) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let (providers, _) = load_config()?;
+    let cidr_file_path =
+        PathBuf::from(env::var("HOME").unwrap() + "/.config/cdnx").join("cidr.txt");
+    fetch_new_data(&providers, &cidr_file_path, verbose).await
+}
+
+/// Background task: re-fetch the CIDR set on the configured `Interval` and
+/// swap it into the shared, read-locked set the socket handlers query,
+/// instead of only checking freshness once at startup.
+async fn background_refresh(cidr_set: Arc<RwLock<CidrSet>>, verbose: bool) {
+    loop {
+        let interval = load_config().map(|(_, i)| i).unwrap_or(172800);
+        tokio::time::sleep(Duration::from_secs(interval)).await;
+
+        let _ = sd_notify::notify(
+            false,
+            &[
+                NotifyState::Reloading,
+                NotifyState::Status("refreshing CIDR set"),
+            ],
+        );
+        if verbose {
+            info!("Refreshing CIDR set ...");
+        }
+        if let Err(e) = refresh_now(verbose).await {
+            if verbose {
+                warn!(format!("Refresh failed: {e}"));
+            }
+        } else {
+            *cidr_set.write().await = CidrSet::build(&read_cidrs());
+        }
+        let _ = sd_notify::notify(
+            false,
+            &[
+                NotifyState::Ready,
+                NotifyState::Status("watching for changes"),
+            ],
+        );
     }
+}
 
-    Ok((ip, prefix_len))
+/// Background task: ping the systemd watchdog at half its configured
+/// interval, when running under a unit with `WatchdogSec=` set.
+async fn watchdog_pings() {
+    let mut usec: u64 = 0;
+    if !sd_notify::watchdog_enabled(false, &mut usec) {
+        return;
+    }
+    let period = Duration::from_micros(usec / 2);
+    loop {
+        tokio::time::sleep(period).await;
+        let _ = sd_notify::notify(false, &[NotifyState::Watchdog]);
+    }
 }
 
-fn ipv4_to_u32(ip: Ipv4Addr) -> u32 {
-    u32::from(ip)
+/// Build the response line(s) for one classified domain, mirroring the
+/// pipeline mode's `domain:port` expansion when ports are configured.
+fn respond(domain: &str, is_this_cdn: bool, allow_print_ports: bool, ports: &[String]) -> Vec<String> {
+    if !allow_print_ports {
+        return vec![if is_this_cdn { "cdn" } else { "not-cdn" }.to_string()];
+    }
+    if !is_this_cdn {
+        ports.iter().map(|port| format!("{domain}:{port}")).collect()
+    } else {
+        vec!["cdn".to_string()]
+    }
 }
 
-fn is_ip_in_cidr(ip: Ipv4Addr, network_ip: Ipv4Addr, prefix_len: u8) -> bool {
-    let ip_u32 = ipv4_to_u32(ip);
-    let network_ip_u32 = ipv4_to_u32(network_ip);
-    let netmask_u32 = !0u32 << (32 - prefix_len);
-    (ip_u32 & netmask_u32) == (network_ip_u32 & netmask_u32)
+/// Serve classification queries over `socket_path`: one domain/IP per line
+/// in, `cdn`/`not-cdn` (or the `domain:port` expansion) per line out. Keeps a
+/// warm resolver and a pre-built `CidrSet` so callers don't pay DNS/trie-build
+/// costs on every invocation the way the one-shot pipeline mode does.
+async fn run_daemon(args: &Args) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let config_dir = PathBuf::from(env::var("HOME").unwrap() + "/.config/cdnx");
+    let socket_path = args
+        .socket
+        .clone()
+        .unwrap_or_else(|| config_dir.join("cdnx.sock"));
+
+    let mut ports: Vec<String> = Vec::new();
+    if let Some(p) = &args.ports {
+        ports = p.split(',').map(|p| p.to_string()).collect();
+    }
+    let allow_print_ports = !ports.is_empty();
+
+    if let Err(e) = check_updates(args.verbose, args.force).await {
+        error!(format!("{e}"));
+        exit(1);
+    }
+    let (resolver_config, resolver_opts) = build_resolver_config(args)?;
+    let resolver: Arc<Resolver> = Arc::new(Resolver::tokio(resolver_config, resolver_opts));
+    let cidr_set: Arc<RwLock<CidrSet>> = Arc::new(RwLock::new(CidrSet::build(&read_cidrs())));
+
+    if socket_path.exists() {
+        let _ = std::fs::remove_file(&socket_path);
+    }
+    let listener = UnixListener::bind(&socket_path)?;
+    info!(format!("Listening on {}", socket_path.display()));
+
+    let _ = sd_notify::notify(false, &[NotifyState::Ready]);
+
+    tokio::spawn(background_refresh(cidr_set.clone(), args.verbose));
+    tokio::spawn(watchdog_pings());
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let resolver = resolver.clone();
+        let cidr_set = cidr_set.clone();
+        let ports = ports.clone();
+
+        tokio::spawn(async move {
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let domain = line.trim().to_string();
+                if domain.is_empty() {
+                    continue;
+                }
+                let Some(ip) = resolve_ip(&domain, &resolver).await else {
+                    let _ = writer.write_all(b"error\n").await;
+                    continue;
+                };
+                let is_this_cdn = is_cdn(&*cidr_set.read().await, &ip);
+                let response = respond(&domain, is_this_cdn, allow_print_ports, &ports);
+                let _ = writer.write_all((response.join("\n") + "\n").as_bytes()).await;
+            }
+        });
+    }
 }
 
 fn read_cidrs() -> Vec<String> {
@@ -273,23 +785,29 @@ fn read_cidrs() -> Vec<String> {
 }
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
+async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     let args = Args::parse();
+
+    if args.daemon {
+        return run_daemon(&args).await;
+    }
+
     let mut ports: Vec<String> = Vec::new();
     let append = args.append;
     let max_concurrent = args.thread;
 
-    if let Some(p) = args.ports {
+    if let Some(p) = &args.ports {
         ports = p.split(',').map(|p| p.to_string()).collect();
     }
-    let allow_print_ports = ports.len() != 0;
-
-    let resolver_config = ResolverConfig::default();
-    let resolver_opts = ResolverOpts::default();
-    let resolver = Arc::from(AsyncResolver::tokio(resolver_config, resolver_opts)?);
+    let allow_print_ports = !ports.is_empty();
 
-    check_updates(args.verbose).await?;
-    let ip_ranges: Arc<Vec<String>> = Arc::from(read_cidrs());
+    if let Err(e) = check_updates(args.verbose, args.force).await {
+        error!(format!("{e}"));
+        exit(1);
+    }
+    let (resolver_config, resolver_opts) = build_resolver_config(&args)?;
+    let resolver: Arc<Resolver> = Arc::new(Resolver::tokio(resolver_config, resolver_opts));
+    let cidr_set: Arc<CidrSet> = Arc::new(CidrSet::build(&read_cidrs()));
 
     let stdin_lock = io::stdin().lock();
     let mut join_set = JoinSet::new();
@@ -297,7 +815,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
     for line in stdin_lock.lines() {
         let domain = line?;
         let resolver_tmp = resolver.clone();
-        let ip_ranges_tmp = ip_ranges.clone();
+        let cidr_set_tmp = cidr_set.clone();
         let ports_tmp = ports.clone();
 
         while join_set.len() >= max_concurrent {
@@ -305,30 +823,31 @@ async fn main() -> Result<(), Box<dyn Error>> {
         }
 
         join_set.spawn(async move {
+            let fqdn = domain.clone().trim_end_matches('.').to_owned() + ".";
             let this_ip = match domain.parse::<IpAddr>() {
-                Ok(__) => domain.clone(),
-                Err(_) => match resolver_tmp
-                    .ipv4_lookup(&(domain.clone().trim_end_matches('.').to_owned() + "."))
-                    .await
-                {
+                Ok(_) => domain.clone(),
+                Err(_) => match resolver_tmp.ipv4_lookup(&fqdn).await {
                     Ok(lookup_result) => lookup_result.iter().next().unwrap().to_string(),
-                    Err(_) => "".to_string(),
+                    Err(_) => match resolver_tmp.ipv6_lookup(&fqdn).await {
+                        Ok(lookup_result) => lookup_result.iter().next().unwrap().to_string(),
+                        Err(_) => "".to_string(),
+                    },
                 },
             };
             if this_ip.is_empty() {
-                return ();
+                return;
             }
-            let is_this_cdn = is_cdn(&ip_ranges_tmp, &this_ip);
+            let is_this_cdn = is_cdn(&cidr_set_tmp, &this_ip);
 
-            if !allow_print_ports && ((is_this_cdn && append) || (!is_this_cdn)) {
+            if !allow_print_ports && (!is_this_cdn || append) {
                 println!("{domain}");
-                return ();
+                return;
             }
 
             if is_this_cdn && append {
                 println!("{domain}:80");
                 println!("{domain}:443");
-                return ();
+                return;
             }
             if !is_this_cdn {
                 for port in ports_tmp.iter() {